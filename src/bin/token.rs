@@ -2,6 +2,7 @@ use hmac::{Hmac, Mac};
 use jwt::SignWithKey;
 use sha2::Sha256;
 use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use std::{env, io};
 
@@ -12,12 +13,24 @@ async fn main() {
         Err(_) => panic!("supply PARTY_TOKEN")
     };
 
+    let ttl: u64 = match env::var("TOKEN_TTL") {
+        Ok(t) => t.trim().parse().expect("TOKEN_TTL must be a number of seconds"),
+        Err(_) => 60 * 60 * 24 * 7,
+    };
+    // guests of a party need the party's signing key and id
+    let party = env::var("PARTY_ID").ok().map(|t| t.trim().to_string());
+    let exp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + ttl;
+
     let mut guest = String::new();
     io::stdin().read_line(&mut guest).unwrap();
 
     let key: Hmac<Sha256> = Hmac::new_from_slice(token.as_bytes()).unwrap();
     let mut claims = BTreeMap::new();
     claims.insert("guest", guest.trim().to_string());
+    claims.insert("exp", exp.to_string());
+    if let Some(party) = party {
+        claims.insert("party", party);
+    }
     let token_str = claims.sign_with_key(&key).unwrap();
 
     println!("{}", token_str);
@@ -1,16 +1,139 @@
-use warp::reject;
+use crate::models::ErrorReply;
+
+use warp::filters::body::BodyDeserializeError;
+use warp::http::StatusCode;
+use warp::{reject, Rejection, Reply};
 use serde::{Serialize, Deserialize};
 
+use std::convert::Infallible;
+use std::fmt;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GuestNotFoundError{
     pub guest: String
 }
 impl reject::Reject for GuestNotFoundError{}
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PartyNotFoundError {
+    pub slug: String,
+}
+impl reject::Reject for PartyNotFoundError {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PartyExistsError {
+    pub slug: String,
+}
+impl reject::Reject for PartyExistsError {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvalidSlugError {
+    pub slug: String,
+}
+impl reject::Reject for InvalidSlugError {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SigningKeyTooShortError {
+    pub min_len: usize,
+}
+impl reject::Reject for SigningKeyTooShortError {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminAuthError;
+impl reject::Reject for AdminAuthError {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DatabaseError;
+impl reject::Reject for DatabaseError {}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenVerificationError;
 impl reject::Reject for TokenVerificationError {}
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenExpiredError;
+impl reject::Reject for TokenExpiredError {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenRevokedError;
+impl reject::Reject for TokenRevokedError {}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthError;
-impl reject::Reject for AuthError {}
\ No newline at end of file
+impl reject::Reject for AuthError {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvalidRsvpStatusError {
+    pub status: String,
+}
+impl reject::Reject for InvalidRsvpStatusError {}
+
+impl fmt::Display for InvalidRsvpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid rsvp status {}", self.status)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RateLimitError;
+impl reject::Reject for RateLimitError {}
+
+pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    let (code, message) = if err.is_not_found() {
+        (StatusCode::NOT_FOUND, "not found".to_string())
+    } else if let Some(e) = err.find::<GuestNotFoundError>() {
+        (StatusCode::NOT_FOUND, format!("guest {} not found", e.guest))
+    } else if let Some(e) = err.find::<PartyNotFoundError>() {
+        (StatusCode::NOT_FOUND, format!("party {} not found", e.slug))
+    } else if let Some(e) = err.find::<PartyExistsError>() {
+        (StatusCode::CONFLICT, format!("party {} already exists", e.slug))
+    } else if let Some(e) = err.find::<InvalidSlugError>() {
+        (StatusCode::BAD_REQUEST, format!("invalid slug {}", e.slug))
+    } else if let Some(e) = err.find::<SigningKeyTooShortError>() {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("signing key must be at least {} bytes", e.min_len),
+        )
+    } else if err.find::<AdminAuthError>().is_some() {
+        (StatusCode::FORBIDDEN, "forbidden".to_string())
+    } else if err.find::<TokenExpiredError>().is_some() {
+        (StatusCode::UNAUTHORIZED, "token expired".to_string())
+    } else if err.find::<TokenRevokedError>().is_some() {
+        (StatusCode::UNAUTHORIZED, "token revoked".to_string())
+    } else if err.find::<TokenVerificationError>().is_some()
+        || err.find::<reject::MissingHeader>().is_some()
+    {
+        (StatusCode::UNAUTHORIZED, "invalid token".to_string())
+    } else if err.find::<AuthError>().is_some() {
+        (StatusCode::UNAUTHORIZED, "authentication failed".to_string())
+    } else if let Some(e) = err.find::<InvalidRsvpStatusError>() {
+        (StatusCode::BAD_REQUEST, e.to_string())
+    } else if err.find::<RateLimitError>().is_some() {
+        (StatusCode::TOO_MANY_REQUESTS, "too many requests".to_string())
+    } else if err.find::<reject::PayloadTooLarge>().is_some() {
+        (StatusCode::PAYLOAD_TOO_LARGE, "request body too large".to_string())
+    } else if err.find::<reject::LengthRequired>().is_some() {
+        (StatusCode::LENGTH_REQUIRED, "content-length required".to_string())
+    } else if let Some(e) = err.find::<BodyDeserializeError>() {
+        (StatusCode::BAD_REQUEST, e.to_string())
+    } else if err.find::<reject::InvalidQuery>().is_some() {
+        (StatusCode::BAD_REQUEST, "invalid query string".to_string())
+    } else if err.find::<reject::UnsupportedMediaType>().is_some() {
+        (StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported media type".to_string())
+    } else if let Some(e) = err.find::<reject::InvalidHeader>() {
+        (StatusCode::BAD_REQUEST, format!("invalid header {}", e.name()))
+    } else if let Some(e) = err.find::<reject::MissingCookie>() {
+        (StatusCode::BAD_REQUEST, format!("missing cookie {}", e.name()))
+    } else if err.find::<reject::MethodNotAllowed>().is_some() {
+        // checked last, sibling routes reject with this whenever only the
+        // method didn't match
+        (StatusCode::METHOD_NOT_ALLOWED, "method not allowed".to_string())
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal server error".to_string())
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&ErrorReply { error: message }),
+        code,
+    ))
+}
@@ -1,62 +1,244 @@
-use crate::errors::AuthError;
-use crate::models::AuthReply;
+use crate::errors::{
+    AuthError, DatabaseError, InvalidSlugError, PartyExistsError, PartyNotFoundError,
+    SigningKeyTooShortError, TokenVerificationError,
+};
+use crate::models::{AuthReply, Guest, StatusReply};
 use crate::party;
+use crate::store::{CreatePartyError, FirestoreStore, Store};
 use crate::{errors::GuestNotFoundError, models};
 
-use jwt::SignWithKey;
+use jwt::{Header, SignWithKey, Token};
+use tracing::{info, warn};
+use warp::http::StatusCode;
 use warp::{reject, Rejection, Reply};
 
 use std::sync::Arc;
 use std::collections::BTreeMap;
+use std::net::IpAddr;
 
-pub type PartyRc = Arc<tokio::sync::RwLock<party::Party>>;
+pub type PartyRc<S = FirestoreStore> = Arc<tokio::sync::RwLock<party::Party<S>>>;
 
-pub async fn hello(party_lock: PartyRc, guest: String) -> Result<impl Reply, Rejection> {
+pub async fn health() -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&StatusReply {
+        status: "ok".to_string(),
+    }))
+}
+
+pub async fn ready<S: Store>(party_lock: PartyRc<S>) -> Result<impl Reply, Rejection> {
+    if party_lock.read().await.ping().await {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&StatusReply {
+                status: "ok".to_string(),
+            }),
+            StatusCode::OK,
+        ))
+    } else {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&StatusReply {
+                status: "unavailable".to_string(),
+            }),
+            StatusCode::SERVICE_UNAVAILABLE,
+        ))
+    }
+}
+
+pub async fn hello<S: Store>(
+    party_lock: PartyRc<S>,
+    guest_id: String,
+) -> Result<impl Reply, Rejection> {
     let party = party_lock.read().await;
-    if let Some(guest) = party.guest(&guest).await {
-        // TODO (sanjay) upon first request to hello delete the passcode
+    if let Some(guest) = party.guest(&guest_id).await {
+        // the passcode is single use, it is shown once and then cleared. Once
+        // the guest's token expires they need a new passcode from the host.
+        let first_visit = !guest.passcode.is_empty();
+        if first_visit {
+            party.clear_passcode(&guest_id).await;
+        }
+        info!(guest = %guest_id, route = "hello", first_visit, "said hello");
         Ok(warp::reply::json(&guest))
     } else {
+        warn!(guest = %guest_id, route = "hello", "guest not found");
+        Err(reject::custom(GuestNotFoundError { guest: guest_id }))
+    }
+}
+
+pub async fn get_guest<S: Store>(
+    party: PartyRc<S>,
+    guest: String,
+) -> Result<impl Reply, Rejection> {
+    if let Some(found) = party.read().await.guest(&guest).await {
+        info!(guest = %guest, route = "rsvp", "fetched rsvp");
+        Ok(warp::reply::json(&found))
+    } else {
+        warn!(guest = %guest, route = "rsvp", "guest not found");
         Err(reject::custom(GuestNotFoundError { guest }))
     }
 }
 
-pub async fn get_guest(party: PartyRc, guest: String) -> Result<impl Reply, Rejection> {
-    if let Some(guest) = party.read().await.guest(&guest).await {
-        Ok(warp::reply::json(&guest))
+pub async fn get_party<S: Store>(
+    slug: String,
+    party_lock: PartyRc<S>,
+    guest: String,
+) -> Result<impl Reply, Rejection> {
+    let party = party_lock.read().await;
+    let guest_party = party.guest(&guest).await.and_then(|guest| guest.party);
+
+    // guests only get to see their own party, any other looks like it
+    // doesn't exist
+    match party.party_by_slug(&slug).await {
+        Some(details) if guest_party.is_some() && details.id == guest_party => {
+            Ok(warp::reply::json(&details))
+        }
+        _ => Err(reject::custom(PartyNotFoundError { slug })),
+    }
+}
+
+/// Shortest party signing key accepted, the output size of the HMAC-SHA256
+/// the key is used with.
+const MIN_SIGNING_KEY_BYTES: usize = 32;
+
+/// Slugs are lowercase letters, digits and dashes, which keeps them safe to
+/// use as a Firestore document id.
+fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+pub async fn create_party<S: Store>(
+    party_lock: PartyRc<S>,
+    new_party: models::NewParty,
+) -> Result<impl Reply, Rejection> {
+    if new_party.signing_key.len() < MIN_SIGNING_KEY_BYTES {
+        warn!(slug = %new_party.slug, route = "admin", "signing key too short");
+        return Err(reject::custom(SigningKeyTooShortError {
+            min_len: MIN_SIGNING_KEY_BYTES,
+        }));
+    }
+
+    // the slug doubles as the party's document id
+    if !is_valid_slug(&new_party.slug) {
+        warn!(slug = %new_party.slug, route = "admin", "invalid slug");
+        return Err(reject::custom(InvalidSlugError { slug: new_party.slug }));
+    }
+
+    let party = party_lock.read().await;
+    match party.create_party(&new_party).await {
+        Ok(created) => {
+            info!(slug = %new_party.slug, route = "admin", "created party");
+            Ok(warp::reply::with_status(
+                warp::reply::json(&created),
+                StatusCode::CREATED,
+            ))
+        }
+        Err(CreatePartyError::Exists) => {
+            warn!(slug = %new_party.slug, route = "admin", "party already exists");
+            Err(reject::custom(PartyExistsError { slug: new_party.slug }))
+        }
+        Err(CreatePartyError::Database) => {
+            warn!(slug = %new_party.slug, route = "admin", "failed to create party");
+            Err(reject::custom(DatabaseError))
+        }
+    }
+}
+
+const DEFAULT_GUEST_PAGE: u32 = 50;
+const MAX_GUEST_PAGE: u32 = 200;
+
+pub async fn list_guests<S: Store>(
+    party_lock: PartyRc<S>,
+    guest: String,
+    query: models::GuestListQuery,
+) -> Result<impl Reply, Rejection> {
+    let limit = query.limit.unwrap_or(DEFAULT_GUEST_PAGE).clamp(1, MAX_GUEST_PAGE);
+    let offset = query.offset.unwrap_or(0);
+
+    let party = party_lock.read().await;
+    if let Some(guest) = party.guest(&guest).await {
+        let page = party.list_guests(guest.party.as_deref(), limit, offset).await;
+        Ok(warp::reply::json(&page))
     } else {
         Err(reject::custom(GuestNotFoundError { guest }))
     }
 }
 
-pub async fn authenticate(
-    party_lock: PartyRc,
+pub async fn authenticate<S: Store>(
+    party_lock: PartyRc<S>,
+    client: Option<IpAddr>,
     auth: models::AuthRequest,
 ) -> Result<impl Reply, Rejection> {
     let party = party_lock.read().await;
-    if let Some(guest) = party.auth(&auth.passcode).await {
+    if let Some(Guest { id: Some(guest), party: guest_party, .. }) =
+        party.auth(&auth.passcode, client).await
+    {
+        let key = match party.key_for(guest_party.as_deref()).await {
+            Some(key) => key,
+            None => {
+                warn!(guest = %guest, route = "auth", "no signing key for guest");
+                return Err(reject::custom(AuthError {}));
+            }
+        };
+
         let mut claims = BTreeMap::new();
-        claims.insert("guest", guest);
+        claims.insert("guest", guest.clone());
+        claims.insert("exp", party.token_expiry().to_string());
+        if let Some(guest_party) = guest_party {
+            claims.insert("party", guest_party);
+        }
 
-        if let Ok(token) = claims.sign_with_key(party.key()) {
+        if let Ok(token) = claims.sign_with_key(&key) {
+            info!(guest = %guest, route = "auth", "issued token");
             Ok(warp::reply::json(&AuthReply { token }))
         } else {
+            warn!(guest = %guest, route = "auth", "failed to sign token");
             Err(reject::custom(AuthError {}))
         }
     } else {
+        warn!(client = ?client, route = "auth", "passcode rejected");
         Err(reject::custom(AuthError {}))
     }
 }
 
-pub async fn update_rsvp(
-    party_lock: PartyRc,
+pub async fn logout<S: Store>(
+    party_lock: PartyRc<S>,
+    _guest: String,
+    token: String,
+) -> Result<impl Reply, Rejection> {
+    // the token was already verified by with_token, only its expiry is needed
+    let unverified: Result<Token<Header, BTreeMap<String, String>, _>, jwt::Error> =
+        Token::parse_unverified(&token);
+    let exp = unverified
+        .ok()
+        .and_then(|t| t.claims().get("exp").and_then(|exp| exp.parse::<u64>().ok()));
+
+    match (token.rsplit('.').next(), exp) {
+        (Some(signature), Some(exp)) => {
+            party_lock.read().await.revoke_token(signature, exp);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        _ => Err(reject::custom(TokenVerificationError)),
+    }
+}
+
+pub async fn update_rsvp<S: Store>(
+    party_lock: PartyRc<S>,
     guest: String,
     rsvp: models::RsvpUpdate,
 ) -> Result<impl Reply, Rejection> {
-    let mut party = party_lock.write().await;
-    if let Some(guest) = party.rsvp(&guest, rsvp.rsvp_status).await {
-        return Ok(warp::reply::json(&guest));
-    } else {
-        Err(reject::custom(GuestNotFoundError { guest }))
+    let party = party_lock.read().await;
+    match party.rsvp(&guest, rsvp.rsvp_status).await {
+        Ok(Some(updated)) => {
+            info!(guest = %guest, route = "rsvp", status = %rsvp.rsvp_status, "updated rsvp");
+            Ok(warp::reply::json(&updated))
+        }
+        Ok(None) => {
+            warn!(guest = %guest, route = "rsvp", "guest not found");
+            Err(reject::custom(GuestNotFoundError { guest }))
+        }
+        Err(err) => {
+            warn!(guest = %guest, route = "rsvp", status = %rsvp.rsvp_status, "invalid rsvp status");
+            Err(reject::custom(err))
+        }
     }
 }
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Per-IP token bucket. Each address may spend up to `capacity` attempts at
+/// once, and the bucket refills evenly over `period`.
+pub struct RateLimiter {
+    capacity: u32,
+    period: Duration,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, period: Duration) -> RateLimiter {
+        RateLimiter {
+            capacity,
+            period,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token for `ip`, returning false when the bucket is empty.
+    pub fn check(&self, ip: IpAddr, now: Instant) -> bool {
+        let capacity = self.capacity as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // a bucket untouched for a full period is back at capacity, so it is
+        // safe to forget it
+        if buckets.len() > 1024 {
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated) < self.period);
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        let refill = elapsed / self.period.as_secs_f64() * capacity;
+        bucket.tokens = (bucket.tokens + refill).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The forwarding header the trusted proxies report the client address in.
+/// Only this header is read, any other one was passed through from the
+/// client and can be forged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ForwardedHeader {
+    XForwardedFor,
+    Forwarded,
+}
+
+impl ForwardedHeader {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ForwardedHeader::XForwardedFor => "X-Forwarded-For",
+            ForwardedHeader::Forwarded => "Forwarded",
+        }
+    }
+}
+
+impl FromStr for ForwardedHeader {
+    type Err = String;
+
+    fn from_str(header: &str) -> Result<ForwardedHeader, String> {
+        if header.eq_ignore_ascii_case("x-forwarded-for") {
+            Ok(ForwardedHeader::XForwardedFor)
+        } else if header.eq_ignore_ascii_case("forwarded") {
+            Ok(ForwardedHeader::Forwarded)
+        } else {
+            Err(format!("unknown forwarding header {}", header))
+        }
+    }
+}
+
+/// The proxies allowed to tell us the client address, and how they do it.
+pub struct ProxyConfig {
+    pub trusted: Vec<IpAddr>,
+    pub header: ForwardedHeader,
+}
+
+/// The address of the client that sent a request arriving from `remote`.
+///
+/// `forwarded` is the value of the configured forwarding header, which is
+/// only believed when the request comes from one of the trusted proxies. The
+/// hops it lists are walked from the nearest one outwards, and the first hop
+/// that isn't a trusted proxy is the client.
+pub fn client_ip(
+    remote: Option<IpAddr>,
+    forwarded: Option<&str>,
+    proxies: &ProxyConfig,
+) -> Option<IpAddr> {
+    let mut client = remote?;
+    if !proxies.trusted.contains(&client) {
+        return Some(client);
+    }
+
+    let hops: Vec<&str> = match (proxies.header, forwarded) {
+        (ForwardedHeader::Forwarded, Some(forwarded)) => forwarded
+            .split(',')
+            .flat_map(|element| element.split(';'))
+            .filter_map(|pair| pair.split_once('='))
+            .filter(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+            .map(|(_, value)| value)
+            .collect(),
+        (ForwardedHeader::XForwardedFor, Some(forwarded_for)) => {
+            forwarded_for.split(',').collect()
+        }
+        (_, None) => Vec::new(),
+    };
+
+    for hop in hops.iter().rev() {
+        match parse_hop(hop) {
+            Some(ip) => {
+                client = ip;
+                if !proxies.trusted.contains(&ip) {
+                    break;
+                }
+            }
+            // an obfuscated or garbled hop, the last proxy is as close to the
+            // client as we can get
+            None => break,
+        }
+    }
+
+    Some(client)
+}
+
+/// Parses a single forwarding hop, which may be quoted, bracketed or carry a
+/// port, e.g. `"[2001:db8::1]:4711"`.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim().trim_matches('"');
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| hop.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
+
+/// Counts failed attempts per IP and locks an address out for `cooldown`
+/// once it reaches `max_failures`. Callers pass the current time in so the
+/// counting doesn't depend on the wall clock.
+pub struct LockoutTracker {
+    max_failures: u32,
+    cooldown: Duration,
+    failures: Mutex<HashMap<IpAddr, Failures>>,
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+impl LockoutTracker {
+    pub fn new(max_failures: u32, cooldown: Duration) -> LockoutTracker {
+        LockoutTracker {
+            max_failures,
+            cooldown,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts an attempt from `ip` as a failure up front, returning false
+    /// instead while `ip` is locked out. Checking and counting happen under
+    /// one lock, so concurrent attempts can't slip past the limit. A
+    /// successful attempt is cleared again with `record_success`.
+    pub fn try_attempt(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut failures = self.failures.lock().unwrap();
+
+        // failures older than the cooldown no longer count towards a lockout
+        if failures.len() > 1024 {
+            failures.retain(|_, f| now.duration_since(f.last) < self.cooldown);
+        }
+
+        let f = failures.entry(ip).or_insert(Failures { count: 0, last: now });
+        if now.duration_since(f.last) >= self.cooldown {
+            f.count = 0;
+        }
+        if f.count >= self.max_failures {
+            return false;
+        }
+
+        f.count += 1;
+        f.last = now;
+        true
+    }
+
+    pub fn record_success(&self, ip: IpAddr) {
+        self.failures.lock().unwrap().remove(&ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROXY: &str = "127.0.0.1";
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn rate_limiter_rejects_past_capacity() {
+        let limiter = RateLimiter::new(3, Duration::from_secs(60));
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check(ip("10.0.0.1"), now));
+        }
+        assert!(!limiter.check(ip("10.0.0.1"), now));
+        // other clients have their own bucket
+        assert!(limiter.check(ip("10.0.0.2"), now));
+        // and the bucket refills over the period
+        assert!(limiter.check(ip("10.0.0.1"), now + Duration::from_secs(20)));
+    }
+
+    fn lockout() -> LockoutTracker {
+        LockoutTracker::new(3, Duration::from_secs(60))
+    }
+
+    #[test]
+    fn lockout_locks_at_threshold() {
+        let lockout = lockout();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(lockout.try_attempt(ip("10.0.0.1"), now));
+        }
+        assert!(!lockout.try_attempt(ip("10.0.0.1"), now));
+        assert!(lockout.try_attempt(ip("10.0.0.2"), now));
+    }
+
+    #[test]
+    fn lockout_unlocks_after_cooldown() {
+        let lockout = lockout();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            lockout.try_attempt(ip("10.0.0.1"), now);
+        }
+        assert!(!lockout.try_attempt(ip("10.0.0.1"), now + Duration::from_secs(59)));
+        assert!(lockout.try_attempt(ip("10.0.0.1"), now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn lockout_success_resets_count() {
+        let lockout = lockout();
+        let now = Instant::now();
+
+        lockout.try_attempt(ip("10.0.0.1"), now);
+        lockout.try_attempt(ip("10.0.0.1"), now);
+        lockout.record_success(ip("10.0.0.1"));
+
+        for _ in 0..3 {
+            assert!(lockout.try_attempt(ip("10.0.0.1"), now));
+        }
+        assert!(!lockout.try_attempt(ip("10.0.0.1"), now));
+    }
+
+    #[test]
+    fn lockout_forgets_stale_failures() {
+        let lockout = lockout();
+        let now = Instant::now();
+
+        lockout.try_attempt(ip("10.0.0.1"), now);
+        lockout.try_attempt(ip("10.0.0.1"), now);
+
+        // the earlier failures are older than the cooldown and no longer
+        // count, so three fresh attempts are allowed
+        let later = now + Duration::from_secs(61);
+        for _ in 0..3 {
+            assert!(lockout.try_attempt(ip("10.0.0.1"), later));
+        }
+        assert!(!lockout.try_attempt(ip("10.0.0.1"), later));
+    }
+
+    fn proxies(header: ForwardedHeader, trusted: &[&str]) -> ProxyConfig {
+        ProxyConfig {
+            trusted: trusted.iter().map(|proxy| ip(proxy)).collect(),
+            header,
+        }
+    }
+
+    #[test]
+    fn client_ip_ignores_headers_from_untrusted_peers() {
+        let proxies = proxies(ForwardedHeader::XForwardedFor, &[PROXY]);
+        let client = client_ip(Some(ip("10.0.0.1")), Some("10.0.0.2"), &proxies);
+        assert_eq!(client, Some(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn client_ip_takes_first_untrusted_hop() {
+        let proxies = proxies(ForwardedHeader::XForwardedFor, &[PROXY, "10.0.0.9"]);
+        let client = client_ip(Some(ip(PROXY)), Some("1.1.1.1, 10.0.0.2, 10.0.0.9"), &proxies);
+        assert_eq!(client, Some(ip("10.0.0.2")));
+    }
+
+    #[test]
+    fn client_ip_reads_forwarded() {
+        let proxies = proxies(ForwardedHeader::Forwarded, &[PROXY]);
+        let client = client_ip(
+            Some(ip(PROXY)),
+            Some("for=10.0.0.1;proto=http, for=\"[2001:db8::1]:4711\""),
+            &proxies,
+        );
+        assert_eq!(client, Some(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn client_ip_falls_back_to_proxy() {
+        let proxies = proxies(ForwardedHeader::Forwarded, &[PROXY]);
+        assert_eq!(client_ip(Some(ip(PROXY)), None, &proxies), Some(ip(PROXY)));
+        assert_eq!(
+            client_ip(Some(ip(PROXY)), Some("for=unknown"), &proxies),
+            Some(ip(PROXY))
+        );
+    }
+
+    #[test]
+    fn forwarded_header_parses() {
+        let header: ForwardedHeader = "x-forwarded-for".parse().unwrap();
+        assert_eq!(header, ForwardedHeader::XForwardedFor);
+        let header: ForwardedHeader = "Forwarded".parse().unwrap();
+        assert_eq!(header, ForwardedHeader::Forwarded);
+        assert!("x-real-ip".parse::<ForwardedHeader>().is_err());
+    }
+}
@@ -1,13 +1,18 @@
 mod errors;
 mod handlers;
+mod limiter;
 mod models;
 mod party;
+mod store;
 
 use std::env;
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use warp::Filter;
 
+use tracing::{info, warn};
 use tracing_subscriber::fmt::format::FmtSpan;
 
 
@@ -23,6 +28,63 @@ async fn main() {
         Err(_) => panic!("supply PROJECT_ID")
     };
 
+    let auth_rate_limit: u32 = match env::var("AUTH_RATE_LIMIT") {
+        Ok(t) => t.trim_end().parse().expect("AUTH_RATE_LIMIT must be a number"),
+        Err(_) => 10,
+    };
+
+    let lockout_max_failures: u32 = match env::var("LOCKOUT_MAX_FAILURES") {
+        Ok(t) => t.trim_end().parse().expect("LOCKOUT_MAX_FAILURES must be a number"),
+        Err(_) => 5,
+    };
+
+    let lockout_cooldown: u64 = match env::var("LOCKOUT_COOLDOWN") {
+        Ok(t) => t.trim_end().parse().expect("LOCKOUT_COOLDOWN must be a number of seconds"),
+        Err(_) => 15 * 60,
+    };
+
+    let token_ttl: u64 = match env::var("TOKEN_TTL") {
+        Ok(t) => t.trim_end().parse().expect("TOKEN_TTL must be a number of seconds"),
+        Err(_) => 60 * 60 * 24 * 7,
+    };
+
+    let admin_key = match env::var("ADMIN_KEY") {
+        Ok(t) => Some(t.trim_end().to_string()),
+        Err(_) => None,
+    };
+
+    let max_body_bytes: u64 = match env::var("MAX_BODY_BYTES") {
+        Ok(t) => t.trim_end().parse().expect("MAX_BODY_BYTES must be a number"),
+        Err(_) => 1024,
+    };
+
+    // proxies allowed to tell us the client address through Forwarded or
+    // X-Forwarded-For. The server only listens on loopback, so by default
+    // that is the local reverse proxy.
+    let trusted_proxies: Vec<IpAddr> = match env::var("TRUSTED_PROXIES") {
+        Ok(t) => t
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+            .map(|proxy| proxy.parse().expect("TRUSTED_PROXIES must be a list of IP addresses"))
+            .collect(),
+        Err(_) => vec![IpAddr::from([127, 0, 0, 1]), IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1])],
+    };
+
+    // the one header those proxies put the client address in, anything else
+    // is passed through from the client
+    let forwarded_header: limiter::ForwardedHeader = match env::var("FORWARDED_HEADER") {
+        Ok(t) => t
+            .trim_end()
+            .parse()
+            .expect("FORWARDED_HEADER must be x-forwarded-for or forwarded"),
+        Err(_) => limiter::ForwardedHeader::XForwardedFor,
+    };
+    let proxies = limiter::ProxyConfig {
+        trusted: trusted_proxies,
+        header: forwarded_header,
+    };
+
     if env::var_os("RUST_LOG").is_none() {
         env::set_var("RUST_LOG", "party=info");
     }
@@ -33,21 +95,57 @@ async fn main() {
     .with_span_events(FmtSpan::CLOSE)
     .init();
 
-    let party = party::Party::new(&project_id, &party_key).await;
+    let lockout = limiter::LockoutTracker::new(
+        lockout_max_failures,
+        Duration::from_secs(lockout_cooldown),
+    );
+    let party = party::Party::new(
+        &project_id,
+        &party_key,
+        Duration::from_secs(token_ttl),
+        lockout,
+    )
+    .await;
     let party = Arc::new(tokio::sync::RwLock::new(party));
 
+    let limiter = limiter::RateLimiter::new(auth_rate_limit, Duration::from_secs(60));
+    let limiter = Arc::new(limiter);
+
+    let cors = warp::cors()
+        .allow_headers(vec![
+            "Content-Type",
+            "Party-Token",
+            "Admin-Key"
+        ])
+        .allow_methods(vec!["GET", "POST"])
+        .allow_credentials(true);
+
+    let cors = match env::var("ALLOWED_ORIGINS") {
+        Ok(origins) => {
+            let origins: Vec<&str> = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .collect();
+            info!("CORS restricted to {:?}", origins);
+            cors.allow_origins(origins)
+        }
+        Err(_) => {
+            warn!("ALLOWED_ORIGINS not set, CORS allows any origin");
+            cors.allow_any_origin()
+        }
+    };
+
     warp::serve(
-        filters::party(party.clone())
-            .with(
-                warp::cors()
-                    .allow_any_origin()
-                    .allow_headers(vec![
-                        "Content-Type",
-                        "Party-Token"
-                    ])
-                    .allow_methods(vec!["GET", "POST"])
-                    .allow_credentials(true),
-            )
+        filters::party(
+            party.clone(),
+            limiter.clone(),
+            Arc::new(proxies),
+            max_body_bytes,
+            admin_key,
+        )
+            .recover(errors::handle_rejection)
+            .with(cors)
             .with(warp::trace::request()),
     )
     .run(([127, 0, 0, 1], 8000))
@@ -57,24 +155,58 @@ async fn main() {
 mod filters {
     use crate::errors;
     use crate::handlers::{self, PartyRc};
+    use crate::limiter::{self, ProxyConfig, RateLimiter};
     use crate::models;
+    use crate::party::unix_now;
+    use crate::store::Store;
 
-    use jwt::{Error, VerifyWithKey};
+    use jwt::{Error, Header, Token, VerifyWithKey};
     use serde::de::DeserializeOwned;
+    use sha2::{Digest, Sha256};
+    use warp::http::HeaderMap;
     use warp::{self, reject, Filter};
 
     use std::collections::BTreeMap;
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::Arc;
+    use std::time::Instant;
 
-    pub fn party(
-        party: PartyRc,
+    pub fn party<S: Store>(
+        party: PartyRc<S>,
+        limiter: Arc<RateLimiter>,
+        proxies: Arc<ProxyConfig>,
+        max_body_bytes: u64,
+        admin_key: Option<String>,
     ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
-        hello(party.clone())
-            .or(rsvp(party.clone()))
-            .or(auth(party.clone()))
+        health()
+            .or(ready(party.clone()))
+            .or(hello(party.clone()))
+            .or(party_details(party.clone()))
+            .or(guests(party.clone()))
+            .or(rsvp(party.clone(), max_body_bytes))
+            .or(auth(party.clone(), limiter, proxies, max_body_bytes))
+            .or(logout(party.clone()))
+            .or(admin_parties(party.clone(), admin_key, max_body_bytes))
     }
 
-    pub fn hello(
-        party: PartyRc,
+    pub fn health(
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        warp::path!("health")
+            .and(warp::get())
+            .and_then(handlers::health)
+    }
+
+    pub fn ready<S: Store>(
+        party: PartyRc<S>,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        warp::path!("ready")
+            .and(warp::get())
+            .and(with_party(party.clone()))
+            .and_then(handlers::ready)
+    }
+
+    pub fn hello<S: Store>(
+        party: PartyRc<S>,
     ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
         warp::path!("hello")
             .and(warp::get())
@@ -83,66 +215,660 @@ mod filters {
             .and_then(handlers::hello)
     }
 
-    pub fn rsvp(
-        party: PartyRc,
+    pub fn party_details<S: Store>(
+        party: PartyRc<S>,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        warp::path!("party" / String)
+            .and(warp::get())
+            .and(with_party(party.clone()))
+            .and(with_token(party.clone()))
+            .and_then(handlers::get_party)
+    }
+
+    pub fn guests<S: Store>(
+        party: PartyRc<S>,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        warp::path!("guests")
+            .and(warp::get())
+            .and(with_party(party.clone()))
+            .and(with_token(party.clone()))
+            .and(warp::query::<models::GuestListQuery>())
+            .and_then(handlers::list_guests)
+    }
+
+    pub fn rsvp<S: Store>(
+        party: PartyRc<S>,
+        max_body_bytes: u64,
     ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
         let get = warp::path!("rsvp")
             .and(warp::get())
             .and(with_party(party.clone()))
-            .and(with_token(party.clone())) // TODO (sanjay) use dynamic signing key
+            .and(with_token(party.clone()))
             .and_then(handlers::get_guest);
 
         let post = warp::path!("rsvp")
             .and(warp::post())
             .and(with_party(party.clone()))
             .and(with_token(party.clone()))
-            .and(with_json::<models::RsvpUpdate>())
+            .and(with_json::<models::RsvpUpdate>(max_body_bytes))
             .and_then(handlers::update_rsvp);
 
         get.or(post)
     }
 
-    pub fn auth(
-        party: PartyRc,
+    pub fn auth<S: Store>(
+        party: PartyRc<S>,
+        limiter: Arc<RateLimiter>,
+        proxies: Arc<ProxyConfig>,
+        max_body_bytes: u64,
     ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
         warp::path!("auth")
             .and(warp::post())
+            .and(with_rate_limit(limiter, proxies.clone()))
             .and(with_party(party.clone()))
-            .and(with_json::<models::AuthRequest>())
+            .and(with_client_ip(proxies))
+            .and(with_json::<models::AuthRequest>(max_body_bytes))
             .and_then(handlers::authenticate)
             .with(warp::trace::named("auth"))
 
     }
 
+    pub fn logout<S: Store>(
+        party: PartyRc<S>,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        warp::path!("logout")
+            .and(warp::post())
+            .and(with_party(party.clone()))
+            .and(with_token(party.clone()))
+            .and(warp::header::header::<String>("Party-Token"))
+            .and_then(handlers::logout)
+    }
+
+    pub fn admin_parties<S: Store>(
+        party: PartyRc<S>,
+        admin_key: Option<String>,
+        max_body_bytes: u64,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        warp::path!("admin" / "parties")
+            .and(warp::post())
+            .and(with_admin(admin_key))
+            .and(with_party(party.clone()))
+            .and(with_json::<models::NewParty>(max_body_bytes))
+            .and_then(handlers::create_party)
+    }
+
+    fn with_admin(
+        admin_key: Option<String>,
+    ) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+        warp::header::optional::<String>("Admin-Key")
+            .and_then(move |key: Option<String>| {
+                let admin_key = admin_key.clone();
+                async move {
+                    match (admin_key, key) {
+                        // compare digests so the comparison time doesn't depend
+                        // on how much of the key was guessed
+                        (Some(expected), Some(key))
+                            if Sha256::digest(expected.as_bytes())
+                                == Sha256::digest(key.as_bytes()) =>
+                        {
+                            Ok(())
+                        }
+                        _ => Err(reject::custom(errors::AdminAuthError)),
+                    }
+                }
+            })
+            .untuple_one()
+    }
+
     fn with_json<T: Send + DeserializeOwned>(
+        max_body_bytes: u64,
     ) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
-        warp::body::content_length_limit(1024).and(warp::body::json())
+        warp::body::content_length_limit(max_body_bytes).and(warp::body::json())
+    }
+
+    fn with_client_ip(
+        proxies: Arc<ProxyConfig>,
+    ) -> impl Filter<Extract = (Option<IpAddr>,), Error = std::convert::Infallible> + Clone {
+        warp::addr::remote()
+            .and(warp::header::headers_cloned())
+            .map(move |remote: Option<SocketAddr>, headers: HeaderMap| {
+                // a proxy may add its own line rather than append to the
+                // client's, so every line of the header is read in order
+                let forwarded: Vec<&str> = headers
+                    .get_all(proxies.header.name())
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .collect();
+                let forwarded = forwarded.join(",");
+                limiter::client_ip(
+                    remote.map(|addr| addr.ip()),
+                    Some(forwarded.as_str()).filter(|f| !f.is_empty()),
+                    &proxies,
+                )
+            })
+    }
+
+    fn with_rate_limit(
+        limiter: Arc<RateLimiter>,
+        proxies: Arc<ProxyConfig>,
+    ) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+        with_client_ip(proxies)
+            .and_then(move |client: Option<IpAddr>| {
+                let limiter = limiter.clone();
+                async move {
+                    match client {
+                        Some(ip) if !limiter.check(ip, Instant::now()) => {
+                            Err(reject::custom(errors::RateLimitError))
+                        }
+                        _ => Ok(()),
+                    }
+                }
+            })
+            .untuple_one()
     }
 
-    fn with_party(
-        party: PartyRc,
-    ) -> impl Filter<Extract = (PartyRc,), Error = std::convert::Infallible> + Clone {
+    fn with_party<S: Store>(
+        party: PartyRc<S>,
+    ) -> impl Filter<Extract = (PartyRc<S>,), Error = std::convert::Infallible> + Clone {
         warp::any().map(move || party.clone())
     }
 
-    fn with_token(
-        party_lock: PartyRc,
+    fn with_token<S: Store>(
+        party_lock: PartyRc<S>,
     ) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
         warp::header::header::<String>("Party-Token")
             .and(with_party(party_lock.clone()))
-            .and_then(|token: String, party_lock: PartyRc| async move {
-                let res: Result<BTreeMap<String, String>, Error> =
-                    token.verify_with_key(party_lock.read().await.key());
+            .and_then(|token: String, party_lock: PartyRc<S>| async move {
+                let party = party_lock.read().await;
 
-                if let Ok(claims) = res {
-                    if let Some(guest) = claims.get("guest") {
+                // each party signs with its own key, so the party claim has to
+                // be read before the signature can be checked
+                let unverified: Result<Token<Header, BTreeMap<String, String>, _>, Error> =
+                    Token::parse_unverified(&token);
+                let party_claim = match unverified {
+                    Ok(unverified) => unverified.claims().get("party").cloned(),
+                    Err(_) => return Err(reject::custom(errors::TokenVerificationError)),
+                };
+                let key = match party.key_for(party_claim.as_deref()).await {
+                    Some(key) => key,
+                    None => return Err(reject::custom(errors::TokenVerificationError)),
+                };
+
+                if token.rsplit('.').next().is_some_and(|sig| party.is_revoked(sig)) {
+                    return Err(reject::custom(errors::TokenRevokedError));
+                }
+
+                let res: Result<BTreeMap<String, String>, Error> = token.verify_with_key(&key);
+
+                let claims = match res {
+                    Ok(claims) => claims,
+                    Err(_) => return Err(reject::custom(errors::TokenVerificationError)),
+                };
+
+                let exp = claims.get("exp").and_then(|exp| exp.parse::<u64>().ok());
+                let guest = match (claims.get("guest"), exp) {
+                    (Some(_), Some(exp)) if exp <= unix_now() => {
+                        return Err(reject::custom(errors::TokenExpiredError))
+                    }
+                    (Some(guest), Some(_)) => guest,
+                    _ => return Err(reject::custom(errors::TokenVerificationError)),
+                };
+
+                // the signature only proves which party issued the token, the
+                // guest also has to belong to that party
+                match party.guest(guest).await {
+                    Some(found) if found.party.as_ref() == claims.get("party") => {
                         Ok(guest.to_string())
-                    } else {
-                        Err(reject::custom(errors::TokenVerificationError))
                     }
-                } else {
-                    Err(reject::custom(errors::TokenVerificationError))
+                    _ => Err(reject::custom(errors::TokenVerificationError)),
                 }
             })
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::limiter::LockoutTracker;
+        use crate::party::Party;
+        use crate::store::memory::MemoryStore;
+
+        use crate::party::unix_now;
+
+        use hmac::{Hmac, Mac};
+        use jwt::SignWithKey;
+        use std::time::Duration;
+        use warp::http::{HeaderValue, StatusCode};
+
+        const PROXY: &str = "127.0.0.1:8000";
+
+        fn filters(
+            store: MemoryStore,
+            limiter: RateLimiter,
+        ) -> impl Filter<Extract = (impl warp::Reply,), Error = std::convert::Infallible> + Clone
+        {
+            admin_filters(store, limiter, None)
+        }
+
+        fn admin_filters(
+            store: MemoryStore,
+            limiter: RateLimiter,
+            admin_key: Option<&str>,
+        ) -> impl Filter<Extract = (impl warp::Reply,), Error = std::convert::Infallible> + Clone
+        {
+            let lockout = LockoutTracker::new(5, Duration::from_secs(60));
+            let party = Party::with_store(store, "secret", Duration::from_secs(60), lockout);
+            let proxies = ProxyConfig {
+                trusted: vec!["127.0.0.1".parse().unwrap()],
+                header: limiter::ForwardedHeader::XForwardedFor,
+            };
+
+            super::party(
+                Arc::new(tokio::sync::RwLock::new(party)),
+                Arc::new(limiter),
+                Arc::new(proxies),
+                1024,
+                admin_key.map(str::to_string),
+            )
+            .recover(errors::handle_rejection)
+        }
+
+        fn token(key: &str, guest: &str, party: Option<&str>, exp: u64) -> String {
+            let key: Hmac<Sha256> = Hmac::new_from_slice(key.as_bytes()).unwrap();
+            let mut claims = BTreeMap::new();
+            claims.insert("guest", guest.to_string());
+            claims.insert("exp", exp.to_string());
+            if let Some(party) = party {
+                claims.insert("party", party.to_string());
+            }
+            claims.sign_with_key(&key).unwrap()
+        }
+
+        fn auth(remote: &str) -> warp::test::RequestBuilder {
+            warp::test::request()
+                .method("POST")
+                .path("/auth")
+                .remote_addr(remote.parse().unwrap())
+                .json(&models::AuthRequest {
+                    passcode: "wrong".to_string(),
+                })
+        }
+
+        #[tokio::test]
+        async fn auth_is_rate_limited() {
+            let limiter = RateLimiter::new(1, Duration::from_secs(60));
+            let filters = filters(MemoryStore::default(), limiter);
+
+            let res = auth("10.0.0.1:1234").reply(&filters).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+            let res = auth("10.0.0.1:1234").reply(&filters).await;
+            assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(res.body(), r#"{"error":"too many requests"}"#);
+        }
+
+        #[tokio::test]
+        async fn auth_rate_limit_is_per_forwarded_client() {
+            let limiter = RateLimiter::new(1, Duration::from_secs(60));
+            let filters = filters(MemoryStore::default(), limiter);
+
+            let res = auth(PROXY).header("X-Forwarded-For", "10.0.0.1").reply(&filters).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+            // a different client behind the same proxy has its own bucket
+            let res = auth(PROXY).header("X-Forwarded-For", "10.0.0.2").reply(&filters).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+            let res = auth(PROXY).header("X-Forwarded-For", "10.0.0.1").reply(&filters).await;
+            assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+
+        #[tokio::test]
+        async fn auth_rate_limit_ignores_injected_forwarded() {
+            let limiter = RateLimiter::new(1, Duration::from_secs(60));
+            let filters = filters(MemoryStore::default(), limiter);
+
+            // the proxy appends the real client to X-Forwarded-For and passes
+            // the client's own Forwarded header through
+            let res = auth(PROXY)
+                .header("X-Forwarded-For", "10.0.0.1")
+                .header("Forwarded", "for=10.0.0.2")
+                .reply(&filters)
+                .await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+            let res = auth(PROXY)
+                .header("X-Forwarded-For", "10.0.0.1")
+                .header("Forwarded", "for=10.0.0.3")
+                .reply(&filters)
+                .await;
+            assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+            // a spoofed X-Forwarded-For hop in front of the proxy's doesn't help
+            let res = auth(PROXY)
+                .header("X-Forwarded-For", "10.0.0.4, 10.0.0.1")
+                .reply(&filters)
+                .await;
+            assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+
+        #[tokio::test]
+        async fn fresh_token_is_accepted() {
+            let store = MemoryStore::default();
+            store.add_guest("alice", "", None);
+            let filters = filters(store, RateLimiter::new(10, Duration::from_secs(60)));
+
+            let res = warp::test::request()
+                .path("/rsvp")
+                .header("Party-Token", token("secret", "alice", None, unix_now() + 60))
+                .reply(&filters)
+                .await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+
+        #[tokio::test]
+        async fn expired_token_is_rejected() {
+            let store = MemoryStore::default();
+            store.add_guest("alice", "", None);
+            let filters = filters(store, RateLimiter::new(10, Duration::from_secs(60)));
+
+            let res = warp::test::request()
+                .path("/rsvp")
+                .header("Party-Token", token("secret", "alice", None, unix_now() - 1))
+                .reply(&filters)
+                .await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(res.body(), r#"{"error":"token expired"}"#);
+        }
+
+        #[tokio::test]
+        async fn token_is_bound_to_its_party() {
+            let store = MemoryStore::default();
+            store.add_party("a", "summer", "key-a");
+            store.add_party("b", "winter", "key-b");
+            store.add_guest("bob", "", Some("b"));
+            let filters = filters(store, RateLimiter::new(10, Duration::from_secs(60)));
+            let exp = unix_now() + 60;
+
+            let rsvp = |token: String| {
+                warp::test::request()
+                    .path("/rsvp")
+                    .header("Party-Token", token)
+                    .reply(&filters)
+            };
+
+            let res = rsvp(token("key-b", "bob", Some("b"), exp)).await;
+            assert_eq!(res.status(), StatusCode::OK);
+
+            // party A's key can't sign for party B
+            let res = rsvp(token("key-a", "bob", Some("b"), exp)).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+            // nor for guests of party B by claiming to be from party A
+            let res = rsvp(token("key-a", "bob", Some("a"), exp)).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+            // or by leaving the party out
+            let res = rsvp(token("secret", "bob", None, exp)).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        #[tokio::test]
+        async fn bad_requests_are_mapped() {
+            let store = MemoryStore::default();
+            store.add_guest("alice", "", None);
+            let filters = filters(store, RateLimiter::new(10, Duration::from_secs(60)));
+            let token = token("secret", "alice", None, unix_now() + 60);
+
+            let cases = [
+                (
+                    warp::test::request().path("/nowhere"),
+                    StatusCode::NOT_FOUND,
+                    r#"{"error":"not found"}"#,
+                ),
+                (
+                    warp::test::request().path("/rsvp"),
+                    StatusCode::UNAUTHORIZED,
+                    r#"{"error":"invalid token"}"#,
+                ),
+                (
+                    warp::test::request()
+                        .path("/rsvp")
+                        .header("Party-Token", HeaderValue::from_bytes(b"\xff").unwrap()),
+                    StatusCode::BAD_REQUEST,
+                    r#"{"error":"invalid header Party-Token"}"#,
+                ),
+                (
+                    warp::test::request().method("PUT").path("/rsvp"),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    r#"{"error":"method not allowed"}"#,
+                ),
+                (
+                    warp::test::request()
+                        .method("POST")
+                        .path("/rsvp")
+                        .header("Party-Token", &token)
+                        .header("Content-Type", "text/plain")
+                        .body("Going"),
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    r#"{"error":"unsupported media type"}"#,
+                ),
+                (
+                    warp::test::request()
+                        .method("POST")
+                        .path("/rsvp")
+                        .header("Party-Token", &token)
+                        .json(&"Going"),
+                    StatusCode::BAD_REQUEST,
+                    r#"{"error":"Request body deserialize error"#,
+                ),
+                (
+                    warp::test::request()
+                        .path("/guests?limit=lots")
+                        .header("Party-Token", &token),
+                    StatusCode::BAD_REQUEST,
+                    r#"{"error":"invalid query string"}"#,
+                ),
+                (
+                    warp::test::request()
+                        .path("/party/nowhere")
+                        .header("Party-Token", &token),
+                    StatusCode::NOT_FOUND,
+                    r#"{"error":"party nowhere not found"}"#,
+                ),
+                (
+                    warp::test::request().method("POST").path("/auth").json(&"hunter2"),
+                    StatusCode::BAD_REQUEST,
+                    r#"{"error":"Request body deserialize error"#,
+                ),
+            ];
+
+            // deserialize errors go on with serde's message, so only the start
+            // of the body is compared
+            for (req, status, body) in cases {
+                let res = req.reply(&filters).await;
+                assert_eq!(res.status(), status, "{}", body);
+                assert!(res.body().starts_with(body.as_bytes()), "{:?}", res.body());
+            }
+        }
+
+        #[tokio::test]
+        async fn oversized_body_is_rejected() {
+            let limiter = RateLimiter::new(10, Duration::from_secs(60));
+            let filters = filters(MemoryStore::default(), limiter);
+
+            let res = warp::test::request()
+                .method("POST")
+                .path("/auth")
+                .json(&models::AuthRequest {
+                    passcode: "a".repeat(2048),
+                })
+                .reply(&filters)
+                .await;
+            assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            assert_eq!(res.body(), r#"{"error":"request body too large"}"#);
+        }
+
+        #[tokio::test]
+        async fn logout_revokes_token() {
+            let store = MemoryStore::default();
+            store.add_guest("alice", "", None);
+            let filters = filters(store, RateLimiter::new(10, Duration::from_secs(60)));
+            let token = token("secret", "alice", None, unix_now() + 60);
+
+            let res = warp::test::request()
+                .method("POST")
+                .path("/logout")
+                .header("Party-Token", &token)
+                .reply(&filters)
+                .await;
+            assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+            let res = warp::test::request()
+                .path("/rsvp")
+                .header("Party-Token", &token)
+                .reply(&filters)
+                .await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(res.body(), r#"{"error":"token revoked"}"#);
+        }
+
+        #[tokio::test]
+        async fn health_is_ok() {
+            let limiter = RateLimiter::new(10, Duration::from_secs(60));
+            let filters = filters(MemoryStore::default(), limiter);
+
+            let res = warp::test::request().path("/health").reply(&filters).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.body(), r#"{"status":"ok"}"#);
+        }
+
+        fn new_party(slug: &str, signing_key: &str) -> models::NewParty {
+            models::NewParty {
+                slug: slug.to_string(),
+                name: "Housewarming".to_string(),
+                time: "2026-11-01T19:00:00Z".parse().unwrap(),
+                location: "Home".to_string(),
+                description: "Come see the new place".to_string(),
+                signing_key: signing_key.to_string(),
+            }
+        }
+
+        fn create_party(admin_key: Option<&str>, signing_key: &str) -> warp::test::RequestBuilder {
+            create_party_with_slug(admin_key, "housewarming", signing_key)
+        }
+
+        fn create_party_with_slug(
+            admin_key: Option<&str>,
+            slug: &str,
+            signing_key: &str,
+        ) -> warp::test::RequestBuilder {
+            let req = warp::test::request()
+                .method("POST")
+                .path("/admin/parties")
+                .json(&new_party(slug, signing_key));
+            match admin_key {
+                Some(key) => req.header("Admin-Key", key),
+                None => req,
+            }
+        }
+
+        #[tokio::test]
+        async fn admin_requires_admin_key() {
+            let key = "k".repeat(32);
+            let limiter = || RateLimiter::new(10, Duration::from_secs(60));
+            let filters = admin_filters(MemoryStore::default(), limiter(), Some("admin-key"));
+
+            for admin_key in [None, Some("wrong-key")] {
+                let res = create_party(admin_key, &key).reply(&filters).await;
+                assert_eq!(res.status(), StatusCode::FORBIDDEN);
+                assert_eq!(res.body(), r#"{"error":"forbidden"}"#);
+            }
+
+            // with ADMIN_KEY unset nobody is an admin
+            let filters = admin_filters(MemoryStore::default(), limiter(), None);
+            let res = create_party(Some("admin-key"), &key).reply(&filters).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        }
+
+        #[tokio::test]
+        async fn admin_creates_party() {
+            let limiter = RateLimiter::new(10, Duration::from_secs(60));
+            let filters = admin_filters(MemoryStore::default(), limiter, Some("admin-key"));
+
+            let res = create_party(Some("admin-key"), &"k".repeat(32)).reply(&filters).await;
+            assert_eq!(res.status(), StatusCode::CREATED);
+            assert!(res.body().starts_with(br#"{"id":"housewarming","slug":"housewarming""#));
+
+            let res = create_party(Some("admin-key"), &"k".repeat(32)).reply(&filters).await;
+            assert_eq!(res.status(), StatusCode::CONFLICT);
+        }
+
+        #[tokio::test]
+        async fn admin_concurrent_creates_conflict() {
+            let limiter = RateLimiter::new(10, Duration::from_secs(60));
+            let filters = admin_filters(MemoryStore::default(), limiter, Some("admin-key"));
+            let key = "k".repeat(32);
+
+            let (a, b) = tokio::join!(
+                create_party(Some("admin-key"), &key).reply(&filters),
+                create_party(Some("admin-key"), &key).reply(&filters),
+            );
+            let mut statuses = [a.status(), b.status()];
+            statuses.sort();
+            assert_eq!(statuses, [StatusCode::CREATED, StatusCode::CONFLICT]);
+        }
+
+        #[tokio::test]
+        async fn admin_rejects_invalid_slug() {
+            let limiter = RateLimiter::new(10, Duration::from_secs(60));
+            let filters = admin_filters(MemoryStore::default(), limiter, Some("admin-key"));
+            let key = "k".repeat(32);
+
+            for slug in ["", "house/warming", "Housewarming", "__party__"] {
+                let res = create_party_with_slug(Some("admin-key"), slug, &key)
+                    .reply(&filters)
+                    .await;
+                assert_eq!(res.status(), StatusCode::BAD_REQUEST, "slug {:?}", slug);
+            }
+        }
+
+        #[tokio::test]
+        async fn admin_rejects_short_signing_key() {
+            let limiter = RateLimiter::new(10, Duration::from_secs(60));
+            let filters = admin_filters(MemoryStore::default(), limiter, Some("admin-key"));
+
+            let res = create_party(Some("admin-key"), &"k".repeat(31)).reply(&filters).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(res.body(), r#"{"error":"signing key must be at least 32 bytes"}"#);
+        }
+
+        #[tokio::test]
+        async fn party_details_are_only_shown_to_its_guests() {
+            let store = MemoryStore::default();
+            store.add_party("a", "summer", "key-a");
+            store.add_party("b", "winter", "key-b");
+            store.add_guest("bob", "", Some("b"));
+            store.add_guest("carol", "", None);
+            let filters = filters(store, RateLimiter::new(10, Duration::from_secs(60)));
+            let bob = token("key-b", "bob", Some("b"), unix_now() + 60);
+            let carol = token("secret", "carol", None, unix_now() + 60);
+
+            let party = |slug: &str, token: &str| {
+                warp::test::request()
+                    .path(&format!("/party/{}", slug))
+                    .header("Party-Token", token)
+                    .reply(&filters)
+            };
+
+            let res = party("winter", &bob).await;
+            assert_eq!(res.status(), StatusCode::OK);
+
+            let res = party("summer", &bob).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+            assert_eq!(res.body(), r#"{"error":"party summer not found"}"#);
+
+            let res = party("winter", &carol).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        }
+    }
 }
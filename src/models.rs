@@ -1,6 +1,15 @@
+use crate::errors::InvalidRsvpStatusError;
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use std::fmt;
+use std::str::FromStr;
+
+/// Stored and sent as the string from `as_str`, so the Firestore form can't
+/// drift from the canonical one.
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(into = "&'static str", try_from = "String")]
 pub enum RsvpStatus {
     Pending,
     Going,
@@ -8,13 +17,147 @@ pub enum RsvpStatus {
     Declined,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl RsvpStatus {
+    /// The canonical form stored in Firestore.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RsvpStatus::Pending => "Pending",
+            RsvpStatus::Going => "Going",
+            RsvpStatus::Maybe => "Maybe",
+            RsvpStatus::Declined => "Declined",
+        }
+    }
+
+    /// Guests can answer an invitation but can't reset it back to pending.
+    pub fn is_user_selectable(&self) -> bool {
+        !matches!(self, RsvpStatus::Pending)
+    }
+}
+
+impl TryFrom<&str> for RsvpStatus {
+    type Error = InvalidRsvpStatusError;
+
+    fn try_from(status: &str) -> Result<RsvpStatus, InvalidRsvpStatusError> {
+        match status {
+            "Pending" => Ok(RsvpStatus::Pending),
+            "Going" => Ok(RsvpStatus::Going),
+            "Maybe" => Ok(RsvpStatus::Maybe),
+            "Declined" => Ok(RsvpStatus::Declined),
+            _ => Err(InvalidRsvpStatusError {
+                status: status.to_string(),
+            }),
+        }
+    }
+}
+
+impl TryFrom<String> for RsvpStatus {
+    type Error = InvalidRsvpStatusError;
+
+    fn try_from(status: String) -> Result<RsvpStatus, InvalidRsvpStatusError> {
+        RsvpStatus::try_from(status.as_str())
+    }
+}
+
+impl From<RsvpStatus> for &'static str {
+    fn from(status: RsvpStatus) -> &'static str {
+        status.as_str()
+    }
+}
+
+impl FromStr for RsvpStatus {
+    type Err = InvalidRsvpStatusError;
+
+    fn from_str(status: &str) -> Result<RsvpStatus, InvalidRsvpStatusError> {
+        RsvpStatus::try_from(status)
+    }
+}
+
+impl fmt::Display for RsvpStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Guest {
     #[serde(alias = "_firestore_id")]
     pub id: Option<String>,
     pub name: String,
     pub status: RsvpStatus,
     pub passcode: String,
+    pub party: Option<String>,
+    /// When the guest document was created, taken from Firestore's own
+    /// metadata rather than a stored field.
+    #[serde(
+        alias = "_firestore_created",
+        with = "firestore::serialize_as_optional_timestamp",
+        default
+    )]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(with = "firestore::serialize_as_optional_timestamp", default)]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// The parts of a guest that other guests are allowed to see.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PublicGuest {
+    pub name: String,
+    pub status: RsvpStatus,
+    #[serde(
+        alias = "_firestore_created",
+        with = "firestore::serialize_as_optional_timestamp",
+        default
+    )]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(with = "firestore::serialize_as_optional_timestamp", default)]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl From<Guest> for PublicGuest {
+    fn from(guest: Guest) -> PublicGuest {
+        PublicGuest {
+            name: guest.name,
+            status: guest.status,
+            created_at: guest.created_at,
+            updated_at: guest.updated_at,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PartyDetails {
+    #[serde(alias = "_firestore_id")]
+    pub id: Option<String>,
+    pub slug: String,
+    pub name: String,
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    pub time: DateTime<Utc>,
+    pub location: String,
+    pub description: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewParty {
+    pub slug: String,
+    pub name: String,
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    pub time: DateTime<Utc>,
+    pub location: String,
+    pub description: String,
+    pub signing_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GuestListQuery {
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GuestPage {
+    pub guests: Vec<PublicGuest>,
+    /// Offset of the next page, absent on the last page.
+    pub next_offset: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -27,7 +170,55 @@ pub struct AuthReply {
     pub token: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusReply {
+    pub status: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorReply {
+    pub error: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RsvpUpdate {
     pub rsvp_status: RsvpStatus
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use firestore::FirestoreValue;
+
+    #[test]
+    fn rsvp_status_round_trips() {
+        let statuses = [
+            RsvpStatus::Pending,
+            RsvpStatus::Going,
+            RsvpStatus::Maybe,
+            RsvpStatus::Declined,
+        ];
+        for status in statuses {
+            let parsed = RsvpStatus::try_from(status.as_str()).unwrap();
+            assert_eq!(parsed.as_str(), status.as_str());
+            let parsed: RsvpStatus = status.to_string().parse().unwrap();
+            assert_eq!(parsed.as_str(), status.as_str());
+
+            // the form stored in firestore is the canonical one
+            let value: FirestoreValue = status.into();
+            let stored = String::deserialize(value).unwrap();
+            assert_eq!(stored, status.as_str());
+            let value: FirestoreValue = stored.into();
+            assert_eq!(RsvpStatus::deserialize(value).unwrap().as_str(), status.as_str());
+        }
+    }
+
+    #[test]
+    fn rsvp_status_rejects_unknown() {
+        let err = RsvpStatus::try_from("going").unwrap_err();
+        assert_eq!(err.status, "going");
+        let value: FirestoreValue = "going".into();
+        assert!(RsvpStatus::deserialize(value).is_err());
+    }
+}
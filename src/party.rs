@@ -1,88 +1,447 @@
-use crate::models::{Guest, RsvpStatus};
+use crate::errors::InvalidRsvpStatusError;
+use crate::limiter::LockoutTracker;
+use crate::models::{Guest, GuestPage, NewParty, PartyDetails, RsvpStatus};
+use crate::store::{CreatePartyError, FirestoreStore, Store};
 
-use firestore::*;
+use firestore::FirestoreDb;
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
 use std::collections::HashMap;
-
-use tracing::*;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub type PartyKey = Hmac<Sha256>;
 
-pub struct Party {
-    db: FirestoreDb,
+/// How long a party's signing key is used before it is read again, so a
+/// rotated key takes effect without a restart.
+const PARTY_KEY_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// How long a party that doesn't exist is remembered. The party claim is read
+/// before the token is verified, so made up parties mustn't each cost a read.
+const MISSING_PARTY_TTL: Duration = Duration::from_secs(60);
+
+struct CachedKey {
+    // None when the party has no usable signing key
+    key: Option<PartyKey>,
+    expires: Instant,
+}
+
+pub struct Party<S = FirestoreStore> {
+    store: S,
     party_key: PartyKey,
+    token_ttl: Duration,
+    // signing keys of parties that have been looked up, by party id
+    party_keys: Mutex<HashMap<String, CachedKey>>,
+    // signatures of revoked tokens, mapped to when the token expires anyway
+    revoked_tokens: Mutex<HashMap<String, u64>>,
+    lockout: LockoutTracker,
+}
+
+/// Seconds since the unix epoch, as used in the `exp` token claim.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
 }
 
 impl Party {
-    pub async fn new(project_id: &str, party_key: &str) -> Party {
+    pub async fn new(
+        project_id: &str,
+        party_key: &str,
+        token_ttl: Duration,
+        lockout: LockoutTracker,
+    ) -> Party {
+        let db = FirestoreDb::new(project_id).await.unwrap();
+
+        Party::with_store(FirestoreStore::new(db), party_key, token_ttl, lockout)
+    }
+}
+
+impl<S: Store> Party<S> {
+    pub fn with_store(
+        store: S,
+        party_key: &str,
+        token_ttl: Duration,
+        lockout: LockoutTracker,
+    ) -> Party<S> {
         Party {
-            db: FirestoreDb::new(project_id).await.unwrap(),
+            store,
             party_key: PartyKey::new_from_slice(party_key.as_bytes()).unwrap(),
+            token_ttl,
+            party_keys: Mutex::new(HashMap::new()),
+            revoked_tokens: Mutex::new(HashMap::new()),
+            lockout,
         }
     }
 
-    pub async fn auth(&self, passcode: &str) -> Option<String> {
-        let query = self
-            .db
-            .fluent()
-            .select()
-            .fields(paths!(Guest::id))
-            .from("guests")
-            .filter(|q| q.for_any(q.field("passcode").eq(passcode)))
-            .obj()
-            .query()
-            .await;
+    /// Finds the guest with `passcode`. Failed attempts are counted against
+    /// `client`, which is refused outright while locked out.
+    pub async fn auth(&self, passcode: &str, client: Option<IpAddr>) -> Option<Guest> {
+        if let Some(ip) = client {
+            if !self.lockout.try_attempt(ip, Instant::now()) {
+                return None;
+            }
+        }
 
-        let mut guests: Vec<HashMap<String, String>> = match query {
-            Ok(guests) => guests,
-            Err(_) => return None,
-        };
+        let guest = self.find_by_passcode(passcode).await;
 
-        if guests.len() != 1 {
+        if let (Some(ip), Some(_)) = (client, &guest) {
+            self.lockout.record_success(ip);
+        }
+
+        guest
+    }
+
+    async fn find_by_passcode(&self, passcode: &str) -> Option<Guest> {
+        // cleared passcodes are stored as empty strings and must never match
+        if passcode.is_empty() {
             return None;
         }
-        
-       guests[0].remove("_firestore_id")
+
+        self.store.find_by_passcode(passcode).await
     }
 
     pub async fn guest(&self, guest: &str) -> Option<Guest> {
-        let res = self
-            .db
-            .fluent()
-            .select()
-            .by_id_in("guests")
-            .obj()
-            .one(guest)
+        self.store.get_guest(guest).await
+    }
+
+    /// Checks that the store is reachable.
+    pub async fn ping(&self) -> bool {
+        self.store.ping().await
+    }
+
+    pub async fn party_by_slug(&self, slug: &str) -> Option<PartyDetails> {
+        self.store.party_by_slug(slug).await
+    }
+
+    pub async fn create_party(&self, party: &NewParty) -> Result<PartyDetails, CreatePartyError> {
+        self.store.create_party(party).await
+    }
+
+    /// Lists a page of the guests of `party`, or of the guests without a
+    /// party when none is given.
+    pub async fn list_guests(&self, party: Option<&str>, limit: u32, offset: u32) -> GuestPage {
+        // ask for one extra guest to find out whether there is another page
+        let mut guests = self
+            .store
+            .list_guests(party, limit.saturating_add(1), offset)
             .await;
+        let next_offset = if guests.len() > limit as usize {
+            guests.truncate(limit as usize);
+            // there is no next page to point at past the largest offset
+            offset.checked_add(limit)
+        } else {
+            None
+        };
+
+        GuestPage { guests, next_offset }
+    }
 
-        match res {
-            Ok(guest) => guest,
-            Err(_) => None,
+    /// Signing key for tokens issued to guests of `party`, read from the
+    /// party document's `signing_key` field. Guests that don't belong to a
+    /// party use the server-wide `PARTY_KEY`.
+    pub async fn key_for(&self, party: Option<&str>) -> Option<PartyKey> {
+        match party {
+            Some(party) => self.party_key_at(party, Instant::now()).await,
+            None => Some(self.party_key.clone()),
         }
     }
 
-    pub fn key(&self) -> &PartyKey {
-        &self.party_key
+    /// Looks up `party`'s signing key as of `now`, going through the cache.
+    async fn party_key_at(&self, party: &str, now: Instant) -> Option<PartyKey> {
+        if let Some(cached) = self.party_keys.lock().unwrap().get(party) {
+            if cached.expires > now {
+                return cached.key.clone();
+            }
+        }
+
+        let key = self
+            .store
+            .signing_key(party)
+            .await
+            .and_then(|signing_key| PartyKey::new_from_slice(signing_key.as_bytes()).ok());
+        let ttl = if key.is_some() {
+            PARTY_KEY_TTL
+        } else {
+            MISSING_PARTY_TTL
+        };
+
+        let mut party_keys = self.party_keys.lock().unwrap();
+        if party_keys.len() > 1024 {
+            party_keys.retain(|_, cached| cached.expires > now);
+        }
+        party_keys.insert(
+            party.to_string(),
+            CachedKey {
+                key: key.clone(),
+                expires: now + ttl,
+            },
+        );
+
+        key
     }
 
-    pub async fn rsvp(&mut self, guest: &str, rsvp: RsvpStatus) -> Option<Guest> {
-        let update = HashMap::from([("status".to_owned(), rsvp)]);
+    /// Revokes the token with `signature` until it would have expired.
+    pub fn revoke_token(&self, signature: &str, exp: u64) {
+        let mut revoked_tokens = self.revoked_tokens.lock().unwrap();
 
-        let res = self
-            .db
-            .fluent()
-            .update()
-            .fields(paths!(Guest::status))
-            .in_col("guests")
-            .document_id(guest)
-            .object(&update)
-            .execute()
-            .await;
+        // expired tokens are rejected regardless, so there is no need to keep
+        // remembering them
+        let now = unix_now();
+        revoked_tokens.retain(|_, exp| *exp > now);
+
+        revoked_tokens.insert(signature.to_string(), exp);
+    }
+
+    pub fn is_revoked(&self, signature: &str) -> bool {
+        self.revoked_tokens.lock().unwrap().contains_key(signature)
+    }
+
+    /// Expiry timestamp for a token issued now.
+    pub fn token_expiry(&self) -> u64 {
+        unix_now() + self.token_ttl.as_secs()
+    }
+
+    /// Records `guest`'s answer. Guests can't reset their answer back to
+    /// pending, which is rejected here.
+    pub async fn rsvp(
+        &self,
+        guest: &str,
+        rsvp: RsvpStatus,
+    ) -> Result<Option<Guest>, InvalidRsvpStatusError> {
+        if !rsvp.is_user_selectable() {
+            return Err(InvalidRsvpStatusError {
+                status: rsvp.to_string(),
+            });
+        }
+
+        Ok(self.store.update_status(guest, rsvp).await)
+    }
+
+    pub async fn clear_passcode(&self, guest: &str) -> Option<Guest> {
+        self.store.clear_passcode(guest).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+
+    fn party(store: MemoryStore) -> Party<MemoryStore> {
+        let lockout = LockoutTracker::new(5, Duration::from_secs(60));
+        Party::with_store(store, "secret", Duration::from_secs(60), lockout)
+    }
 
-        match res {
-            Ok(guest) => guest,
-            Err(_) => None,
+    #[tokio::test]
+    async fn auth_finds_guest_by_passcode() {
+        let store = MemoryStore::default();
+        store.add_guest("alice", "hunter2", None);
+        let party = party(store);
+
+        let guest = party.auth("hunter2", None).await.unwrap();
+        assert_eq!(guest.id.as_deref(), Some("alice"));
+        assert!(party.auth("wrong", None).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn auth_rejects_empty_passcode() {
+        let store = MemoryStore::default();
+        // a guest whose passcode has already been cleared
+        store.add_guest("alice", "", None);
+        let party = party(store);
+
+        assert!(party.auth("", None).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn auth_locks_out_client() {
+        let store = MemoryStore::default();
+        store.add_guest("alice", "hunter2", None);
+        let party = party(store);
+        let client = Some("10.0.0.1".parse().unwrap());
+
+        for _ in 0..5 {
+            assert!(party.auth("wrong", client).await.is_none());
+        }
+        // even the right passcode is refused while locked out
+        assert!(party.auth("hunter2", client).await.is_none());
+        assert!(party.auth("hunter2", None).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn rsvp_rejects_pending() {
+        let store = MemoryStore::default();
+        store.add_guest("alice", "hunter2", None);
+        let party = party(store);
+
+        let err = party.rsvp("alice", RsvpStatus::Pending).await.unwrap_err();
+        assert_eq!(err.status, "Pending");
+    }
+
+    #[tokio::test]
+    async fn rsvp_accepts_answers() {
+        let store = MemoryStore::default();
+        store.add_guest("alice", "hunter2", None);
+        let party = party(store);
+
+        // guests can change their mind between any of the answers
+        let answers = [
+            RsvpStatus::Going,
+            RsvpStatus::Maybe,
+            RsvpStatus::Declined,
+            RsvpStatus::Going,
+        ];
+        for status in answers {
+            let guest = party.rsvp("alice", status).await.unwrap().unwrap();
+            assert_eq!(guest.status.as_str(), status.as_str());
         }
+        assert!(party.rsvp("bob", RsvpStatus::Going).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn list_guests_pages() {
+        let store = MemoryStore::default();
+        for i in 0..5 {
+            store.add_guest(&format!("guest{}", i), "", Some("p"));
+        }
+        let party = party(store);
+
+        let page = party.list_guests(Some("p"), 2, 0).await;
+        assert_eq!(page.guests.len(), 2);
+        assert_eq!(page.next_offset, Some(2));
+
+        let page = party.list_guests(Some("p"), 2, 4).await;
+        assert_eq!(page.guests.len(), 1);
+        assert_eq!(page.next_offset, None);
+    }
+
+    #[tokio::test]
+    async fn key_for_uses_party_signing_key() {
+        let store = MemoryStore::default();
+        store.add_party("p", "summer", "party-secret");
+        let party = party(store);
+
+        let expected = PartyKey::new_from_slice(b"party-secret").unwrap();
+        let key = party.key_for(Some("p")).await.unwrap();
+        assert_eq!(key.finalize().into_bytes(), expected.finalize().into_bytes());
+        assert!(party.key_for(Some("missing")).await.is_none());
+
+        let key = party.key_for(None).await.unwrap();
+        let fallback = PartyKey::new_from_slice(b"secret").unwrap();
+        assert_eq!(key.finalize().into_bytes(), fallback.finalize().into_bytes());
+    }
+
+    #[tokio::test]
+    async fn list_guests_keeps_parties_apart() {
+        let store = MemoryStore::default();
+        store.add_guest("alice", "", Some("p"));
+        store.add_guest("bob", "", Some("q"));
+        store.add_guest("carol", "", None);
+        let party = party(store);
+
+        let names = |page: GuestPage| -> Vec<String> {
+            page.guests.into_iter().map(|g| g.name).collect()
+        };
+        assert_eq!(names(party.list_guests(Some("p"), 10, 0).await), ["alice"]);
+        assert_eq!(names(party.list_guests(None, 10, 0).await), ["carol"]);
+    }
+
+    #[test]
+    fn revoked_tokens_are_swept_once_expired() {
+        let party = party(MemoryStore::default());
+        let now = unix_now();
+
+        party.revoke_token("stale", now - 1);
+        party.revoke_token("fresh", now + 60);
+        assert!(party.is_revoked("fresh"));
+        assert!(!party.is_revoked("other"));
+
+        // revoking another token sweeps the one that has expired anyway
+        assert!(!party.is_revoked("stale"));
+    }
+
+    #[tokio::test]
+    async fn rsvp_advances_updated_at() {
+        let store = MemoryStore::default();
+        store.add_guest("alice", "hunter2", None);
+        let party = party(store);
+
+        assert!(party.guest("alice").await.unwrap().updated_at.is_none());
+
+        let first = party.rsvp("alice", RsvpStatus::Going).await.unwrap().unwrap();
+        let first = first.updated_at.unwrap();
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        let second = party.rsvp("alice", RsvpStatus::Maybe).await.unwrap().unwrap();
+        assert!(second.updated_at.unwrap() > first);
+    }
+
+    #[tokio::test]
+    async fn list_guests_walks_every_page() {
+        let store = MemoryStore::default();
+        for i in 0..7 {
+            store.add_guest(&format!("guest{}", i), "", Some("p"));
+        }
+        let party = party(store);
+
+        let mut names = Vec::new();
+        let mut offset = Some(0);
+        while let Some(next) = offset {
+            let page = party.list_guests(Some("p"), 3, next).await;
+            names.extend(page.guests.into_iter().map(|g| g.name));
+            offset = page.next_offset;
+        }
+
+        let expected: Vec<String> = (0..7).map(|i| format!("guest{}", i)).collect();
+        assert_eq!(names, expected);
+    }
+
+    #[tokio::test]
+    async fn list_guests_offset_does_not_overflow() {
+        let store = MemoryStore::default();
+        store.add_guest("alice", "", None);
+        let party = party(store);
+
+        let page = party.list_guests(None, u32::MAX, u32::MAX).await;
+        assert!(page.guests.is_empty());
+        assert_eq!(page.next_offset, None);
+    }
+
+    #[tokio::test]
+    async fn party_by_slug_finds_party() {
+        let store = MemoryStore::default();
+        store.add_party("p", "summer", "party-secret");
+        let party = party(store);
+
+        let details = party.party_by_slug("summer").await.unwrap();
+        assert_eq!(details.id.as_deref(), Some("p"));
+        assert!(party.party_by_slug("winter").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn party_key_lookups_are_cached() {
+        let party = party(MemoryStore::default());
+        let now = Instant::now();
+
+        // a missing party is remembered for a while
+        assert!(party.party_key_at("p", now).await.is_none());
+        party.store.add_party("p", "summer", "first-secret");
+        assert!(party.party_key_at("p", now).await.is_none());
+
+        let later = now + MISSING_PARTY_TTL;
+        let first = PartyKey::new_from_slice(b"first-secret").unwrap();
+        let key = party.party_key_at("p", later).await.unwrap();
+        assert_eq!(key.finalize().into_bytes(), first.clone().finalize().into_bytes());
+
+        // a rotated key is picked up once the cached one expires
+        party.store.set_signing_key("p", "second-secret");
+        let key = party.party_key_at("p", later).await.unwrap();
+        assert_eq!(key.finalize().into_bytes(), first.finalize().into_bytes());
+
+        let second = PartyKey::new_from_slice(b"second-secret").unwrap();
+        let key = party.party_key_at("p", later + PARTY_KEY_TTL).await.unwrap();
+        assert_eq!(key.finalize().into_bytes(), second.finalize().into_bytes());
     }
 }
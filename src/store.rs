@@ -0,0 +1,415 @@
+use crate::models::{Guest, NewParty, PartyDetails, PublicGuest, RsvpStatus};
+
+use chrono::{DateTime, Utc};
+use firestore::errors::FirestoreError;
+use firestore::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+
+/// Storage for the guest list. `Party` only talks to guests through this
+/// trait so its logic can run against something other than Firestore.
+pub trait GuestStore {
+    fn get_guest(&self, guest: &str) -> impl Future<Output = Option<Guest>> + Send;
+
+    /// Returns the only guest with `passcode`, if there is exactly one.
+    fn find_by_passcode(&self, passcode: &str) -> impl Future<Output = Option<Guest>> + Send;
+
+    fn update_status(
+        &self,
+        guest: &str,
+        status: RsvpStatus,
+    ) -> impl Future<Output = Option<Guest>> + Send;
+
+    fn clear_passcode(&self, guest: &str) -> impl Future<Output = Option<Guest>> + Send;
+
+    /// Lists guests of `party`, or the guests without a party when no party
+    /// is given, in document id order.
+    fn list_guests(
+        &self,
+        party: Option<&str>,
+        limit: u32,
+        offset: u32,
+    ) -> impl Future<Output = Vec<PublicGuest>> + Send;
+}
+
+/// Storage for parties and their signing keys.
+pub trait PartyStore {
+    /// Checks that the backing store is reachable.
+    fn ping(&self) -> impl Future<Output = bool> + Send;
+
+    fn party_by_slug(&self, slug: &str) -> impl Future<Output = Option<PartyDetails>> + Send;
+
+    /// Stores `party` under its slug, failing with `CreatePartyError::Exists`
+    /// when a party with that slug is already there.
+    fn create_party(
+        &self,
+        party: &NewParty,
+    ) -> impl Future<Output = Result<PartyDetails, CreatePartyError>> + Send;
+
+    /// The raw `signing_key` of the party with id `party`.
+    fn signing_key(&self, party: &str) -> impl Future<Output = Option<String>> + Send;
+}
+
+/// Why a party couldn't be stored.
+#[derive(Debug, PartialEq)]
+pub enum CreatePartyError {
+    /// A party with the same slug already exists.
+    Exists,
+    Database,
+}
+
+/// Everything `Party` needs from its storage, shareable across warp's
+/// worker threads.
+pub trait Store: GuestStore + PartyStore + Send + Sync + 'static {}
+
+impl<T: GuestStore + PartyStore + Send + Sync + 'static> Store for T {}
+
+#[derive(Serialize, Deserialize)]
+struct StatusUpdate {
+    status: RsvpStatus,
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    updated_at: DateTime<Utc>,
+}
+
+pub struct FirestoreStore {
+    db: FirestoreDb,
+}
+
+impl FirestoreStore {
+    pub fn new(db: FirestoreDb) -> FirestoreStore {
+        FirestoreStore { db }
+    }
+}
+
+impl GuestStore for FirestoreStore {
+    async fn get_guest(&self, guest: &str) -> Option<Guest> {
+        let res = self
+            .db
+            .fluent()
+            .select()
+            .by_id_in("guests")
+            .obj()
+            .one(guest)
+            .await;
+
+        res.unwrap_or_default()
+    }
+
+    async fn find_by_passcode(&self, passcode: &str) -> Option<Guest> {
+        let query: FirestoreResult<Vec<Guest>> = self
+            .db
+            .fluent()
+            .select()
+            .from("guests")
+            .filter(|q| q.for_any(q.field("passcode").eq(passcode)))
+            .obj()
+            .query()
+            .await;
+
+        let mut guests = query.ok()?;
+        if guests.len() != 1 {
+            return None;
+        }
+
+        guests.pop()
+    }
+
+    async fn update_status(&self, guest: &str, status: RsvpStatus) -> Option<Guest> {
+        let update = StatusUpdate {
+            status,
+            updated_at: Utc::now(),
+        };
+
+        let res = self
+            .db
+            .fluent()
+            .update()
+            .fields(paths!(Guest::{status, updated_at}))
+            .in_col("guests")
+            .document_id(guest)
+            .object(&update)
+            .execute()
+            .await;
+
+        res.unwrap_or_default()
+    }
+
+    async fn clear_passcode(&self, guest: &str) -> Option<Guest> {
+        let update = HashMap::from([("passcode".to_owned(), String::new())]);
+
+        let res = self
+            .db
+            .fluent()
+            .update()
+            .fields(paths!(Guest::passcode))
+            .in_col("guests")
+            .document_id(guest)
+            .object(&update)
+            .execute()
+            .await;
+
+        res.unwrap_or_default()
+    }
+
+    async fn list_guests(&self, party: Option<&str>, limit: u32, offset: u32) -> Vec<PublicGuest> {
+        let party = match party {
+            Some(party) => party,
+            None => return self.list_guests_without_party(limit, offset).await,
+        };
+
+        let res = self
+            .db
+            .fluent()
+            .select()
+            .fields(paths!(PublicGuest::{name, status, updated_at}))
+            .from("guests")
+            .filter(|q| q.for_all([q.field("party").eq(party)]))
+            .order_by([("__name__", FirestoreQueryDirection::Ascending)])
+            .offset(offset)
+            .limit(limit)
+            .obj()
+            .query()
+            .await;
+
+        res.unwrap_or_default()
+    }
+}
+
+impl FirestoreStore {
+    /// Guests from before parties existed have no `party` field at all, and
+    /// firestore can't query for a missing field, so the guests without a
+    /// party are picked out and paged here instead.
+    async fn list_guests_without_party(&self, limit: u32, offset: u32) -> Vec<PublicGuest> {
+        let res: FirestoreResult<Vec<Guest>> = self
+            .db
+            .fluent()
+            .select()
+            .from("guests")
+            .order_by([("__name__", FirestoreQueryDirection::Ascending)])
+            .obj()
+            .query()
+            .await;
+
+        res.unwrap_or_default()
+            .into_iter()
+            .filter(|guest| guest.party.is_none())
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(PublicGuest::from)
+            .collect()
+    }
+}
+
+impl PartyStore for FirestoreStore {
+    async fn ping(&self) -> bool {
+        // the cheapest query we can make
+        let res = self
+            .db
+            .fluent()
+            .select()
+            .fields(paths!(Guest::id))
+            .from("guests")
+            .limit(1)
+            .query()
+            .await;
+
+        res.is_ok()
+    }
+
+    async fn party_by_slug(&self, slug: &str) -> Option<PartyDetails> {
+        let res: FirestoreResult<Vec<PartyDetails>> = self
+            .db
+            .fluent()
+            .select()
+            .fields(paths!(PartyDetails::{slug, name, time, location, description}))
+            .from("parties")
+            .filter(|q| q.for_all([q.field("slug").eq(slug)]))
+            .limit(1)
+            .obj()
+            .query()
+            .await;
+
+        res.ok()?.pop()
+    }
+
+    async fn create_party(&self, party: &NewParty) -> Result<PartyDetails, CreatePartyError> {
+        // the slug is the document id, so firestore itself refuses a second
+        // party with the same slug, even when two creates race
+        let res = self
+            .db
+            .fluent()
+            .insert()
+            .into("parties")
+            .document_id(&party.slug)
+            .return_only_fields(paths!(PartyDetails::{slug, name, time, location, description}))
+            .object(party)
+            .execute()
+            .await;
+
+        match res {
+            Ok(details) => Ok(details),
+            Err(FirestoreError::DataConflictError(_)) => Err(CreatePartyError::Exists),
+            Err(_) => Err(CreatePartyError::Database),
+        }
+    }
+
+    async fn signing_key(&self, party: &str) -> Option<String> {
+        let res: FirestoreResult<Option<HashMap<String, String>>> = self
+            .db
+            .fluent()
+            .select()
+            .fields(["signing_key"])
+            .by_id_in("parties")
+            .obj()
+            .one(party)
+            .await;
+
+        res.ok()??.remove("signing_key")
+    }
+}
+
+/// An in-memory store for tests.
+#[cfg(test)]
+pub mod memory {
+    use super::{CreatePartyError, GuestStore, PartyStore};
+    use crate::models::{Guest, NewParty, PartyDetails, PublicGuest, RsvpStatus};
+
+    use chrono::Utc;
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    pub struct MemoryStore {
+        // keyed by id, which is the order firestore lists guests in
+        guests: Mutex<BTreeMap<String, Guest>>,
+        parties: Mutex<Vec<(PartyDetails, String)>>,
+    }
+
+    impl MemoryStore {
+        pub fn add_guest(&self, id: &str, passcode: &str, party: Option<&str>) {
+            let guest = Guest {
+                id: Some(id.to_string()),
+                name: id.to_string(),
+                status: RsvpStatus::Pending,
+                passcode: passcode.to_string(),
+                party: party.map(str::to_string),
+                created_at: Some(Utc::now()),
+                updated_at: None,
+            };
+            self.guests.lock().unwrap().insert(id.to_string(), guest);
+        }
+
+        pub fn add_party(&self, id: &str, slug: &str, signing_key: &str) {
+            let details = PartyDetails {
+                id: Some(id.to_string()),
+                slug: slug.to_string(),
+                name: slug.to_string(),
+                time: Utc::now(),
+                location: String::new(),
+                description: String::new(),
+            };
+            self.parties
+                .lock()
+                .unwrap()
+                .push((details, signing_key.to_string()));
+        }
+
+        pub fn set_signing_key(&self, id: &str, signing_key: &str) {
+            let mut parties = self.parties.lock().unwrap();
+            for (details, key) in parties.iter_mut() {
+                if details.id.as_deref() == Some(id) {
+                    *key = signing_key.to_string();
+                }
+            }
+        }
+    }
+
+    impl GuestStore for MemoryStore {
+        async fn get_guest(&self, guest: &str) -> Option<Guest> {
+            self.guests.lock().unwrap().get(guest).cloned()
+        }
+
+        async fn find_by_passcode(&self, passcode: &str) -> Option<Guest> {
+            let guests = self.guests.lock().unwrap();
+            let mut found = guests.values().filter(|g| g.passcode == passcode);
+            match (found.next(), found.next()) {
+                (Some(guest), None) => Some(guest.clone()),
+                _ => None,
+            }
+        }
+
+        async fn update_status(&self, guest: &str, status: RsvpStatus) -> Option<Guest> {
+            let mut guests = self.guests.lock().unwrap();
+            let found = guests.get_mut(guest)?;
+            found.status = status;
+            found.updated_at = Some(Utc::now());
+            Some(found.clone())
+        }
+
+        async fn clear_passcode(&self, guest: &str) -> Option<Guest> {
+            let mut guests = self.guests.lock().unwrap();
+            let found = guests.get_mut(guest)?;
+            found.passcode.clear();
+            Some(found.clone())
+        }
+
+        async fn list_guests(
+            &self,
+            party: Option<&str>,
+            limit: u32,
+            offset: u32,
+        ) -> Vec<PublicGuest> {
+            // like firestore, a guest without a party matches no party filter,
+            // and is only listed when asking for the guests without one
+            let guests = self.guests.lock().unwrap();
+            guests
+                .values()
+                .filter(|g| g.party.as_deref() == party)
+                .skip(offset as usize)
+                .take(limit as usize)
+                .cloned()
+                .map(PublicGuest::from)
+                .collect()
+        }
+    }
+
+    impl PartyStore for MemoryStore {
+        async fn ping(&self) -> bool {
+            true
+        }
+
+        async fn party_by_slug(&self, slug: &str) -> Option<PartyDetails> {
+            let parties = self.parties.lock().unwrap();
+            parties
+                .iter()
+                .find(|(details, _)| details.slug == slug)
+                .map(|(details, _)| details.clone())
+        }
+
+        async fn create_party(&self, party: &NewParty) -> Result<PartyDetails, CreatePartyError> {
+            let mut parties = self.parties.lock().unwrap();
+            if parties.iter().any(|(details, _)| details.id.as_deref() == Some(&party.slug)) {
+                return Err(CreatePartyError::Exists);
+            }
+            let details = PartyDetails {
+                id: Some(party.slug.clone()),
+                slug: party.slug.clone(),
+                name: party.name.clone(),
+                time: party.time,
+                location: party.location.clone(),
+                description: party.description.clone(),
+            };
+            parties.push((details.clone(), party.signing_key.clone()));
+            Ok(details)
+        }
+
+        async fn signing_key(&self, party: &str) -> Option<String> {
+            let parties = self.parties.lock().unwrap();
+            parties
+                .iter()
+                .find(|(details, _)| details.id.as_deref() == Some(party))
+                .map(|(_, key)| key.clone())
+        }
+    }
+}